@@ -36,9 +36,15 @@
 //! ```
 
 use linear_map::LinearMap;
-use device::{IDevice, DeviceType};
+use device::{IDevice, DeviceType, Stream};
+use framework::IFramework;
 use memory::MemoryType;
+use std::cell::RefCell;
 use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut, Range};
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
+use std::time::Instant;
 use std::{fmt, mem, error};
 
 // #[derive(Debug)]
@@ -47,8 +53,28 @@ use std::{fmt, mem, error};
 #[allow(missing_debug_implementations)] // due to LinearMap
 pub struct SharedMemory<T> {
     latest_location: DeviceType,
-    latest_copy: MemoryType,
+    /// Wrapped in `ManuallyDrop` so `Drop for SharedMemory` can decide whether to run
+    /// `MemoryType`'s destructor itself, instead of the compiler doing so unconditionally: an
+    /// imported copy from `from_raw` must never have its destructor run at all.
+    latest_copy: ManuallyDrop<MemoryType>,
     copies: LinearMap<DeviceType, MemoryType>,
+    pending: LinearMap<DeviceType, Stream>,
+    /// The element range that `sync_range` last refreshed on a copy. Absent means no
+    /// `sync_range` has left the copy partially refreshed since its last full `sync`.
+    partial: LinearMap<DeviceType, Range<usize>>,
+    /// The pool copies are recycled through, if this `SharedMemory` was created with one.
+    pool: Option<Rc<RefCell<MemoryPool>>>,
+    /// Per-device resident-memory budgets set through `set_budget`.
+    budgets: LinearMap<DeviceType, u64>,
+    /// When each tracked, non-latest copy in `copies` was last touched. `evict_stale` does not
+    /// read it: it frees at most the one copy held for the device it is given.
+    last_access: RefCell<LinearMap<DeviceType, Instant>>,
+    /// Devices that held a copy which `evict_stale` freed. Still tracked, so `sync` silently
+    /// re-allocates and re-syncs them on next access instead of erroring as "never tracked".
+    evicted: LinearMap<DeviceType, ()>,
+    /// Whether this `SharedMemory` owns (and so must free) the copy tracked for a device.
+    /// `false` for copies wrapped through `from_raw`, which belong to whoever imported them.
+    owned: LinearMap<DeviceType, bool>,
     cap: usize,
     phantom: PhantomData<T>,
 }
@@ -57,108 +83,551 @@ impl<T> SharedMemory<T> {
     /// Create new SharedMemory by allocating [Memory][1] on a Device.
     /// [1]: ../memory/index.html
     pub fn new(dev: &DeviceType, capacity: usize) -> Result<SharedMemory<T>, Error> {
-        let copies = LinearMap::<DeviceType, MemoryType>::new();
-        let copy: MemoryType;
+        let copy = try!(Self::allocate_on(dev, Self::mem_size(capacity)));
+        Ok(SharedMemory {
+            latest_location: dev.clone(),
+            latest_copy: ManuallyDrop::new(copy),
+            copies: LinearMap::new(),
+            pending: LinearMap::new(),
+            partial: LinearMap::new(),
+            pool: None,
+            budgets: LinearMap::new(),
+            last_access: RefCell::new(LinearMap::new()),
+            evicted: LinearMap::new(),
+            owned: { let mut owned = LinearMap::new(); owned.insert(dev.clone(), true); owned },
+            cap: capacity,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Create new SharedMemory like `new`, but first try to claim an already-allocated buffer
+    /// of sufficient size from `pool` instead of allocating a fresh one. Copies freed by
+    /// `drop_device`, or by this `SharedMemory` being dropped, are returned to the same `pool`
+    /// so that repeatedly creating and destroying same-sized buffers doesn't thrash the
+    /// framework allocator.
+    pub fn with_pool(pool: Rc<RefCell<MemoryPool>>, dev: &DeviceType, capacity: usize) -> Result<SharedMemory<T>, Error> {
         let alloc_size = Self::mem_size(capacity);
-        match *dev {
-            DeviceType::Native(ref cpu) => copy = MemoryType::Native(try!(cpu.alloc_memory(alloc_size as u64))),
-            DeviceType::OpenCL(ref context) => copy = MemoryType::OpenCL(try!(context.alloc_memory(alloc_size as u64))),
-            #[cfg(feature = "cuda")]
-            DeviceType::Cuda(ref context) => copy = MemoryType::Cuda(try!(context.alloc_memory(alloc_size as u64))),
-        }
+        let copy = match pool.borrow_mut().claim(dev, alloc_size) {
+            Some(memory) => memory,
+            None => try!(Self::allocate_on(dev, alloc_size)),
+        };
         Ok(SharedMemory {
             latest_location: dev.clone(),
-            latest_copy: copy,
-            copies: copies,
+            latest_copy: ManuallyDrop::new(copy),
+            copies: LinearMap::new(),
+            pending: LinearMap::new(),
+            partial: LinearMap::new(),
+            pool: Some(pool),
+            budgets: LinearMap::new(),
+            last_access: RefCell::new(LinearMap::new()),
+            evicted: LinearMap::new(),
+            owned: { let mut owned = LinearMap::new(); owned.insert(dev.clone(), true); owned },
+            cap: capacity,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Wrap memory already allocated on `device` by something outside this crate as a
+    /// `SharedMemory`, without allocating. Ownership stays with the caller: this
+    /// `SharedMemory` never frees the wrapped buffer, whether through `drop_device` or its own
+    /// `Drop`. Use `export` to hand the same handle back out, e.g. to pass it to another
+    /// runtime.
+    pub fn from_raw(device: &DeviceType, handle: ExternalMemory, capacity: usize) -> Result<SharedMemory<T>, Error> {
+        let copy = try!(Self::wrap_external(device, handle, Self::mem_size(capacity)));
+        Ok(SharedMemory {
+            latest_location: device.clone(),
+            latest_copy: ManuallyDrop::new(copy),
+            copies: LinearMap::new(),
+            pending: LinearMap::new(),
+            partial: LinearMap::new(),
+            pool: None,
+            budgets: LinearMap::new(),
+            last_access: RefCell::new(LinearMap::new()),
+            evicted: LinearMap::new(),
+            owned: { let mut owned = LinearMap::new(); owned.insert(device.clone(), false); owned },
             cap: capacity,
             phantom: PhantomData,
         })
     }
 
+    /// Hand back the raw handle backing the copy on `device`, without relinquishing ownership
+    /// of it: this `SharedMemory` still owns (and will free) the copy unless it was itself
+    /// imported through `from_raw`.
+    pub fn export(&self, device: &DeviceType) -> Result<ExternalMemory, Error> {
+        let memory = try!(self.peek(device).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device.")));
+        match (device, memory) {
+            (&DeviceType::Native(_), &MemoryType::Native(ref flat)) => Ok(ExternalMemory::Native(flat.as_ptr())),
+            (&DeviceType::OpenCL(_), &MemoryType::OpenCL(ref mem)) => Ok(ExternalMemory::OpenCL(mem.as_raw())),
+            #[cfg(feature = "cuda")]
+            (&DeviceType::Cuda(_), &MemoryType::Cuda(ref mem)) => Ok(ExternalMemory::Cuda(mem.as_raw())),
+            _ => Err(Error::InvalidMemory("Memory type does not match the framework of the requested device.")),
+        }
+    }
+
+    /// Wrap an `ExternalMemory` handle as the `MemoryType` matching `device`'s framework.
+    fn wrap_external(device: &DeviceType, handle: ExternalMemory, bytes: usize) -> Result<MemoryType, Error> {
+        match (device, handle) {
+            (&DeviceType::Native(_), ExternalMemory::Native(ptr)) => Ok(MemoryType::Native(unsafe { ::memory::FlatBox::from_raw(ptr, bytes) })),
+            (&DeviceType::OpenCL(_), ExternalMemory::OpenCL(handle)) => Ok(MemoryType::OpenCL(unsafe { ::memory::opencl::Memory::from_raw(handle) })),
+            #[cfg(feature = "cuda")]
+            (&DeviceType::Cuda(_), ExternalMemory::Cuda(ptr)) => Ok(MemoryType::Cuda(unsafe { ::memory::cuda::Memory::from_raw(ptr) })),
+            _ => Err(Error::InvalidMemory("ExternalMemory handle does not match the framework of the requested device.")),
+        }
+    }
+
+    /// Allocate a fresh buffer of `bytes` size on `device`, bypassing any pool.
+    fn allocate_on(device: &DeviceType, bytes: usize) -> Result<MemoryType, Error> {
+        match *device {
+            DeviceType::Native(ref cpu) => Ok(MemoryType::Native(try!(cpu.alloc_memory(bytes as u64)))),
+            DeviceType::OpenCL(ref context) => Ok(MemoryType::OpenCL(try!(context.alloc_memory(bytes as u64)))),
+            #[cfg(feature = "cuda")]
+            DeviceType::Cuda(ref context) => Ok(MemoryType::Cuda(try!(context.alloc_memory(bytes as u64)))),
+        }
+    }
+
     /// Synchronize memory from latest location to `destination`.
     pub fn sync(&mut self, destination: &DeviceType) -> Result<(), Error> {
+        try!(self.await_pending(destination));
+        try!(self.reclaim_evicted(destination));
         if &self.latest_location != destination {
             let latest = self.latest_location.clone();
-            try!(self.sync_from_to(&latest, &destination));
-            self.latest_location = destination.clone();
-            self.latest_copy = try!(self.copies.remove(destination).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device.")));
+            try!(self.sync_from_to(&latest, &destination, None));
+            let copy = try!(self.copies.remove(destination).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device.")));
+            let previous = mem::replace(&mut self.latest_location, destination.clone());
+            let previous_copy = mem::replace(&mut self.latest_copy, ManuallyDrop::new(copy));
+            self.copies.insert(previous, ManuallyDrop::into_inner(previous_copy));
         }
+        self.partial.remove(destination);
         Ok(())
     }
 
+    /// Synchronize only `range` (element indices) of memory from the latest location to
+    /// `destination`, leaving the rest of the destination's existing copy untouched.
+    ///
+    /// This is cheaper than `sync` when only a slice of the buffer changed, at the cost of
+    /// leaving `destination`'s copy only partially up to date: a subsequent `sync` (or a
+    /// `sync_range` covering a different sub-region) on that device performs the missing work
+    /// rather than silently returning stale elements.
+    pub fn sync_range(&mut self, destination: &DeviceType, range: Range<usize>) -> Result<(), Error> {
+        try!(self.await_pending(destination));
+        try!(self.reclaim_evicted(destination));
+        if range.end > self.cap || range.start > range.end {
+            return Err(Error::InvalidMemory("Range exceeds the capacity of this SharedMemory."));
+        }
+        if &self.latest_location != destination {
+            let latest = self.latest_location.clone();
+            try!(self.sync_from_to(&latest, &destination, Some(range.clone())));
+            self.partial.insert(destination.clone(), range);
+        }
+        Ok(())
+    }
+
+    /// Synchronize memory from latest location to `destination` without blocking.
+    ///
+    /// The transfer is enqueued on `stream` and the returned [`SyncHandle`][1] resolves once
+    /// the stream signals completion. While a transfer to `destination` is in flight, `sync`,
+    /// `get` and `get_mut` on that device block to join the pending handle first instead of
+    /// racing the copy, and re-invoking `sync_async` on it returns [`Error::TransferInFlight`][2];
+    /// `latest_location`/`latest_copy` are only swapped once the handle is joined, since the
+    /// data on `destination` is not valid until then.
+    ///
+    /// [1]: ./struct.SyncHandle.html
+    /// [2]: ./enum.Error.html#variant.TransferInFlight
+    pub fn sync_async(&mut self, destination: &DeviceType, stream: Stream) -> Result<SyncHandle, Error> {
+        if self.pending.contains_key(destination) {
+            return Err(Error::TransferInFlight("SharedMemory already has a transfer in flight for this device."));
+        }
+        if &self.latest_location == destination {
+            return Err(Error::InvalidMemoryAllocation("SharedMemory is already synchronized with this device."));
+        }
+        let source = self.latest_location.clone();
+        match self.aquire_copies(&source, destination) {
+            Ok((source_copy, mut destination_copy)) => {
+                match destination {
+                    &DeviceType::Native(ref cpu) => {
+                        match destination_copy.as_mut_native() {
+                            Some(ref mut mem) => try!(cpu.sync_in_async(&source, &source_copy, mem, None, &stream)),
+                            None => return Err(Error::InvalidMemory("Expected Native Memory (FlatBox)")),
+                        }
+                    },
+                    &DeviceType::OpenCL(ref context) => {
+                        match destination_copy.as_mut_opencl() {
+                            Some(ref mut mem) => try!(context.sync_in_async(&source, &source_copy, mem, None, &stream)),
+                            None => return Err(Error::InvalidMemory("Expected OpenCL Memory.")),
+                        }
+                    },
+                    #[cfg(feature = "cuda")]
+                    &DeviceType::Cuda(ref context) => {
+                        match destination_copy.as_mut_cuda() {
+                            Some(ref mut mem) => try!(context.sync_in_async(&source, &source_copy, mem, None, &stream)),
+                            None => return Err(Error::InvalidMemory("Expected CUDA Memory.")),
+                        }
+                    }
+                }
+                self.return_copies(&source, source_copy, destination, destination_copy);
+                self.pending.insert(destination.clone(), stream.clone());
+                Ok(SyncHandle { source: source, destination: destination.clone(), stream: stream })
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Blocks until the transfer behind a pending `SyncHandle` for `device` has completed, then
+    /// finalizes it by making `device` the new `latest_location`. A no-op if nothing is pending.
+    fn await_pending(&mut self, device: &DeviceType) -> Result<(), Error> {
+        if let Some(stream) = self.pending.remove(device) {
+            try!(stream.sync());
+            if &self.latest_location != device {
+                let copy = try!(self.copies.remove(device).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device.")));
+                let previous = mem::replace(&mut self.latest_location, device.clone());
+                let previous_copy = mem::replace(&mut self.latest_copy, ManuallyDrop::new(copy));
+                self.copies.insert(previous, ManuallyDrop::into_inner(previous_copy));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finalizes a transfer completed through a [`SyncHandle`][1], making `destination` the new
+    /// `latest_location`. Called by `SyncHandle::join`.
+    ///
+    /// [1]: ./struct.SyncHandle.html
+    fn finish_async(&mut self, destination: &DeviceType) -> Result<(), Error> {
+        self.pending.remove(destination);
+        if &self.latest_location != destination {
+            let copy = try!(self.copies.remove(destination).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device.")));
+            let previous = mem::replace(&mut self.latest_location, destination.clone());
+            let previous_copy = mem::replace(&mut self.latest_copy, ManuallyDrop::new(copy));
+            self.copies.insert(previous, ManuallyDrop::into_inner(previous_copy));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the copy on `device` is only partially up to date, i.e. was last
+    /// refreshed through `sync_range` rather than a full `sync`.
+    pub fn is_partial(&self, device: &DeviceType) -> bool {
+        self.partial.contains_key(device)
+    }
+
+    /// Get a reference to the full, up-to-date memory copy on `device`, performing a full
+    /// `sync` first if the existing copy was left partially synchronized by `sync_range`.
+    pub fn get_synced(&mut self, device: &DeviceType) -> Result<&MemoryType, Error> {
+        if self.partial.contains_key(device) || self.evicted.contains_key(device) {
+            try!(self.sync(device));
+        }
+        self.get(device).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device."))
+    }
+
+    /// Borrow the copy on `device` as a host-visible, read-only slice, syncing the latest data
+    /// to it first if necessary.
+    ///
+    /// This is the ergonomic replacement for `get(device).unwrap().as_native().unwrap().as_slice()`:
+    /// for `Native` devices it borrows the `FlatBox` directly; for `CUDA`/`OpenCL` devices it
+    /// transparently stages through a tracked `Native` copy, since this crate does not yet
+    /// expose pinned/host-mapped device memory.
+    pub fn map_read(&mut self, device: &DeviceType) -> Result<MappedSlice<T>, Error> {
+        let host = try!(self.map_onto_host(device));
+        match self.get(&host).and_then(|mem| mem.as_native()) {
+            Some(flat) => Ok(MappedSlice { slice: flat.as_slice() }),
+            None => Err(Error::InvalidMemory("Expected Native Memory (FlatBox)")),
+        }
+    }
+
+    /// Borrow the copy on `device` as a host-visible, mutable slice, syncing the latest data
+    /// to it first if necessary. When the returned `MappedSliceMut` is dropped, `device` becomes
+    /// the new `latest_location`, so other devices are re-synced on their next access.
+    pub fn map_write(&mut self, device: &DeviceType) -> Result<MappedSliceMut<T>, Error> {
+        let host = try!(self.map_onto_host(device));
+        Ok(MappedSliceMut { shared: self, host: host, writeback: device.clone() })
+    }
+
+    /// Makes sure the latest data is visible on some `Native` device, returning it. For a
+    /// `Native` `device` this is `device` itself; for `CUDA`/`OpenCL` it is a tracked `Native`
+    /// copy that the data gets staged through, reusing one already tracked by this
+    /// `SharedMemory` or, like `sync_via_host`, adding a fresh scratch one if it has none.
+    fn map_onto_host(&mut self, device: &DeviceType) -> Result<DeviceType, Error> {
+        try!(self.sync(device));
+        match *device {
+            DeviceType::Native(_) => Ok(device.clone()),
+            _ => {
+                let host = match self.tracked_native_device() {
+                    Some(host) => host,
+                    None => {
+                        let host = try!(Self::temp_native_device());
+                        try!(self.add_device(&host));
+                        host
+                    }
+                };
+                try!(self.sync(&host));
+                Ok(host)
+            }
+        }
+    }
+
     /// Get a reference to the memory copy on the provided `device`.
     ///
-    /// Returns `None` if there is no memory copy on the device.
-    pub fn get(&self, device: &DeviceType) -> Option<&MemoryType> {
+    /// If a transfer enqueued by `sync_async` is still in flight for `device`, blocks on it by
+    /// joining the pending `SyncHandle` first rather than racing the copy. If `evict_stale`
+    /// previously freed the copy on `device`, or if the existing copy was left only partially
+    /// up to date by `sync_range`, transparently resyncs it first, the same as `sync`/
+    /// `get_synced`. Returns `None` if any of those steps fails, or if there is no memory copy
+    /// on the device.
+    pub fn get(&mut self, device: &DeviceType) -> Option<&MemoryType> {
+        if self.pending.contains_key(device) {
+            if self.await_pending(device).is_err() {
+                return None
+            }
+        }
+        if self.evicted.contains_key(device) || self.partial.contains_key(device) {
+            if self.sync(device).is_err() {
+                return None
+            }
+        }
         // first check if device is not current location. This is cheaper than a lookup in `copies`.
         if &self.latest_location == device {
-            return Some(&self.latest_copy)
+            return Some(&*self.latest_copy)
         }
+        self.touch(device);
         self.copies.get(device)
     }
 
     /// Get a mutable reference to the memory copy on the provided `device`.
     ///
-    /// Returns `None` if there is no memory copy on the device.
+    /// If a transfer enqueued by `sync_async` is still in flight for `device`, blocks on it by
+    /// joining the pending `SyncHandle` first rather than racing the copy. If `evict_stale`
+    /// previously freed the copy on `device`, or if the existing copy was left only partially
+    /// up to date by `sync_range`, transparently resyncs it first, the same as `sync`/
+    /// `get_synced`. Returns `None` if any of those steps fails, or if there is no memory copy
+    /// on the device.
     pub fn get_mut(&mut self, device: &DeviceType) -> Option<&mut MemoryType> {
+        if self.pending.contains_key(device) {
+            if self.await_pending(device).is_err() {
+                return None
+            }
+        }
+        if self.evicted.contains_key(device) || self.partial.contains_key(device) {
+            if self.sync(device).is_err() {
+                return None
+            }
+        }
         // first check if device is not current location. This is cheaper than a lookup in `copies`.
         if &self.latest_location == device {
-            return Some(&mut self.latest_copy)
+            return Some(&mut *self.latest_copy)
         }
+        self.touch(device);
         self.copies.get_mut(device)
     }
 
+    /// Like `get`, but never joins a pending transfer or reclaims an evicted copy: for either
+    /// case it just returns `None`, the same as for an untracked device. Used by callers that
+    /// only hold a shared reference to `self` (`export`, and the read side of `map_write`'s
+    /// mapped slice).
+    fn peek(&self, device: &DeviceType) -> Option<&MemoryType> {
+        if self.pending.contains_key(device) {
+            return None
+        }
+        if &self.latest_location == device {
+            return Some(&*self.latest_copy)
+        }
+        self.touch(device);
+        self.copies.get(device)
+    }
+
     /// Synchronize memory from `source` device to `destination` device.
-    fn sync_from_to(&mut self, source: &DeviceType, destination: &DeviceType) -> Result<(), Error> {
-        if source != destination {
-            match self.aquire_copies(source, destination) {
-                Ok((mut source_copy, mut destination_copy)) => {
-                    match destination {
-                        &DeviceType::Native(ref cpu) => {
-                            match destination_copy.as_mut_native() {
-                                Some(ref mut mem) => try!(cpu.sync_in(source, &source_copy, mem)),
-                                None => return Err(Error::InvalidMemory("Expected Native Memory (FlatBox)"))
-                            }
-                        },
-                        &DeviceType::OpenCL(ref context) => unimplemented!(),
-                        #[cfg(feature = "cuda")]
-                        &DeviceType::Cuda(ref context) => {
-                            match destination_copy.as_mut_cuda() {
-                                Some(ref mut mem) => try!(context.sync_in(source, &source_copy, mem)),
-                                None => return Err(Error::InvalidMemory("Expected CUDA Memory."))
-                            }
+    ///
+    /// `range` restricts the transfer to those elements, converted to a byte offset/length
+    /// before being handed to the framework backend; `None` copies the whole buffer. When
+    /// `source` and `destination` are on the same framework, this prefers a direct
+    /// device-to-device copy (see `can_access_peer`) over staging through host memory, falling
+    /// back to a host bounce buffer when peer access is unavailable.
+    fn sync_from_to(&mut self, source: &DeviceType, destination: &DeviceType, range: Option<Range<usize>>) -> Result<(), Error> {
+        if source == destination {
+            return Ok(());
+        }
+        let byte_range = range.clone().map(|r| Self::mem_size(r.start)..Self::mem_size(r.end));
+        if Self::same_framework(source, destination) {
+            if self.can_access_peer(source, destination) {
+                return self.sync_peer_to_peer(source, destination, byte_range);
+            }
+            return self.sync_via_host(source, destination, range);
+        }
+        match self.aquire_copies(source, destination) {
+            Ok((mut source_copy, mut destination_copy)) => {
+                match destination {
+                    &DeviceType::Native(ref cpu) => {
+                        match destination_copy.as_mut_native() {
+                            Some(ref mut mem) => try!(cpu.sync_in(source, &source_copy, mem, byte_range)),
+                            None => return Err(Error::InvalidMemory("Expected Native Memory (FlatBox)"))
+                        }
+                    },
+                    &DeviceType::OpenCL(ref context) => {
+                        match destination_copy.as_mut_opencl() {
+                            Some(ref mut mem) => try!(context.sync_in(source, &source_copy, mem, byte_range)),
+                            None => return Err(Error::InvalidMemory("Expected OpenCL Memory."))
+                        }
+                    },
+                    #[cfg(feature = "cuda")]
+                    &DeviceType::Cuda(ref context) => {
+                        match destination_copy.as_mut_cuda() {
+                            Some(ref mut mem) => try!(context.sync_in(source, &source_copy, mem, byte_range)),
+                            None => return Err(Error::InvalidMemory("Expected CUDA Memory."))
                         }
                     }
-                    self.return_copies(source, source_copy, destination, destination_copy);
-                    Ok(())
-                },
-                Err(err) => Err(err),
+                }
+                self.return_copies(source, source_copy, destination, destination_copy);
+                Ok(())
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns `true` if `a` and `b` belong to the same framework (e.g. both CUDA, both
+    /// OpenCL), making a direct device-to-device copy between them possible in principle.
+    fn same_framework(a: &DeviceType, b: &DeviceType) -> bool {
+        match (a, b) {
+            (&DeviceType::OpenCL(_), &DeviceType::OpenCL(_)) => true,
+            #[cfg(feature = "cuda")]
+            (&DeviceType::Cuda(_), &DeviceType::Cuda(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if `a` can directly access `b`'s memory (or vice versa), allowing
+    /// `sync_from_to` to issue a peer-to-peer copy instead of staging through host memory.
+    /// Always `false` for devices on different frameworks, or for Native devices, which have
+    /// no peer concept.
+    pub fn can_access_peer(&self, a: &DeviceType, b: &DeviceType) -> bool {
+        match (a, b) {
+            (&DeviceType::OpenCL(ref ctx_a), &DeviceType::OpenCL(ref ctx_b)) => ctx_a.can_access_peer(ctx_b),
+            #[cfg(feature = "cuda")]
+            (&DeviceType::Cuda(ref ctx_a), &DeviceType::Cuda(ref ctx_b)) => ctx_a.can_access_peer(ctx_b),
+            _ => false,
+        }
+    }
+
+    /// Issue a direct device-to-device copy between two same-framework devices, bypassing host
+    /// memory entirely. Only called once `can_access_peer` has confirmed direct access.
+    fn sync_peer_to_peer(&mut self, source: &DeviceType, destination: &DeviceType, byte_range: Option<Range<usize>>) -> Result<(), Error> {
+        match self.aquire_copies(source, destination) {
+            Ok((source_copy, mut destination_copy)) => {
+                match (source, destination) {
+                    (&DeviceType::OpenCL(ref src_ctx), &DeviceType::OpenCL(ref dst_ctx)) => {
+                        match (source_copy.as_opencl(), destination_copy.as_mut_opencl()) {
+                            (Some(src_mem), Some(ref mut dst_mem)) => try!(dst_ctx.copy_peer(src_ctx, src_mem, dst_mem, byte_range)),
+                            _ => return Err(Error::InvalidMemory("Expected OpenCL Memory on both sides."))
+                        }
+                    },
+                    #[cfg(feature = "cuda")]
+                    (&DeviceType::Cuda(ref src_ctx), &DeviceType::Cuda(ref dst_ctx)) => {
+                        match (source_copy.as_cuda(), destination_copy.as_mut_cuda()) {
+                            (Some(src_mem), Some(ref mut dst_mem)) => try!(dst_ctx.copy_peer(src_ctx, src_mem, dst_mem, byte_range)),
+                            _ => return Err(Error::InvalidMemory("Expected CUDA Memory on both sides."))
+                        }
+                    },
+                    _ => return Err(Error::InvalidMemory("sync_peer_to_peer called for devices on different frameworks.")),
+                }
+                self.return_copies(source, source_copy, destination, destination_copy);
+                Ok(())
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Stage a same-framework transfer through a Native copy when no peer access path is
+    /// available between `source` and `destination`. Reuses an already-tracked Native device if
+    /// this `SharedMemory` has one; otherwise spins up a scratch Native device and an untracked
+    /// temporary copy on it just for the duration of the bounce, e.g. for two CUDA devices with
+    /// no peer access and no Native device ever added.
+    fn sync_via_host(&mut self, source: &DeviceType, destination: &DeviceType, range: Option<Range<usize>>) -> Result<(), Error> {
+        match self.tracked_native_device() {
+            Some(host) => {
+                // The tracked host might currently be the target of a `sync_async` transfer;
+                // join it first so this synchronous bounce doesn't race that DMA/copy engine.
+                try!(self.await_pending(&host));
+                try!(self.sync_from_to(source, &host, range.clone()));
+                self.sync_from_to(&host, destination, range)
+            },
+            None => {
+                let host = try!(Self::temp_native_device());
+                let bytes = Self::mem_size(self.capacity());
+                let copy = try!(Self::allocate_on(&host, bytes));
+                self.copies.insert(host.clone(), copy);
+                let result = self.sync_from_to(source, &host, range.clone())
+                    .and_then(|_| self.sync_from_to(&host, destination, range));
+                self.copies.remove(&host);
+                result
             }
-        } else {
-            Ok(())
         }
     }
 
+    /// Create a new, unshared `Native` device to stage a peer-less transfer through, for
+    /// frameworks/context combinations where this `SharedMemory` isn't already tracking one.
+    fn temp_native_device() -> Result<DeviceType, Error> {
+        let native = ::frameworks::Native::new();
+        let hardwares = native.hardwares();
+        native.new_device(hardwares).map_err(|_| Error::InvalidMemoryAllocation("Could not create a temporary Native device to bounce a peer-less transfer through."))
+    }
+
+    /// Returns a `Native` device this `SharedMemory` already holds a copy on, if any.
+    fn tracked_native_device(&self) -> Option<DeviceType> {
+        if let DeviceType::Native(_) = self.latest_location {
+            return Some(self.latest_location.clone());
+        }
+        self.copies.keys().find(|d| match **d { DeviceType::Native(_) => true, _ => false }).cloned()
+    }
+
     /// Aquire ownership over the copies for synchronizing.
+    ///
+    /// The latest copy lives in the dedicated `latest_copy` field, not in `copies` (it only
+    /// moves into `copies` once it stops being latest), so `source`/`destination` are checked
+    /// against `latest_location` and taken from there instead of failing a `copies` lookup.
     fn aquire_copies(&mut self, source: &DeviceType, destination: &DeviceType) -> Result<(MemoryType, MemoryType), Error> {
-        let source_copy: MemoryType;
-        let destination_copy: MemoryType;
-        match self.copies.remove(source) {
-            Some(source_cpy) => source_copy = source_cpy,
-            None => return Err(Error::MissingSource("SharedMemory does not hold a copy on source device."))
-        }
-        match self.copies.remove(destination) {
-            Some(destination_cpy) => destination_copy = destination_cpy,
-            None => return Err(Error::MissingDestination("SharedMemory does not hold a copy on destination device."))
-        }
+        let source_copy = if source == &self.latest_location {
+            self.take_latest_copy()
+        } else {
+            match self.copies.remove(source) {
+                Some(copy) => copy,
+                None => return Err(Error::MissingSource("SharedMemory does not hold a copy on source device.")),
+            }
+        };
+        let destination_copy = if destination == &self.latest_location {
+            self.take_latest_copy()
+        } else {
+            match self.copies.remove(destination) {
+                Some(copy) => copy,
+                None => {
+                    self.restore_copy(source, source_copy);
+                    return Err(Error::MissingDestination("SharedMemory does not hold a copy on destination device."));
+                }
+            }
+        };
 
         Ok((source_copy, destination_copy))
     }
 
+    /// Take ownership of `latest_copy`, via the `ManuallyDrop` wrapper rather than a placeholder
+    /// swap: nothing reads `latest_copy` between `aquire_copies` and its matching
+    /// `return_copies`/`restore_copy` within a single `sync_from_to`/`sync_peer_to_peer`/
+    /// `sync_async` call, so the momentarily-emptied `ManuallyDrop` is never observed.
+    fn take_latest_copy(&mut self) -> MemoryType {
+        unsafe { ManuallyDrop::take(&mut self.latest_copy) }
+    }
+
+    /// Put a copy removed by `aquire_copies` back where it belongs: into `latest_copy` if
+    /// `device` is still the latest location, or into `copies` otherwise.
+    fn restore_copy(&mut self, device: &DeviceType, copy: MemoryType) {
+        if device == &self.latest_location {
+            self.latest_copy = ManuallyDrop::new(copy);
+        } else {
+            self.copies.insert(device.clone(), copy);
+        }
+    }
+
     /// Return ownership over the copies after synchronizing.
     fn return_copies(&mut self, src: &DeviceType, src_mem: MemoryType, dest: &DeviceType, dest_mem: MemoryType) {
-        self.copies.insert(src.clone(), src_mem);
-        self.copies.insert(dest.clone(), dest_mem);
+        self.restore_copy(src, src_mem);
+        self.restore_copy(dest, dest_mem);
+        self.touch(src);
+        self.touch(dest);
     }
 
     /// Track a new `device` and allocate memory on it.
@@ -172,19 +641,127 @@ impl<T> SharedMemory<T> {
         match self.copies.get(device) {
             Some(_) => Err(Error::InvalidMemoryAllocation("SharedMemory already tracks memory for this device. No memory allocation.")),
             None => {
-                let copy: MemoryType;
-                match *device {
-                    DeviceType::Native(ref cpu) => copy = MemoryType::Native(try!(cpu.alloc_memory(Self::mem_size(self.capacity()) as u64))),
-                    DeviceType::OpenCL(ref context) => copy = MemoryType::OpenCL(try!(context.alloc_memory(Self::mem_size(self.capacity()) as u64))),
-                    #[cfg(feature = "cuda")]
-                    DeviceType::Cuda(ref context) => copy = MemoryType::Cuda(try!(context.alloc_memory(Self::mem_size(self.capacity()) as u64))),
+                let bytes = Self::mem_size(self.capacity());
+                let claimed = match self.pool {
+                    Some(ref pool) => pool.borrow_mut().claim(device, bytes),
+                    None => None,
+                };
+                let copy = match claimed {
+                    Some(memory) => memory,
+                    None => try!(Self::allocate_on(device, bytes)),
                 };
                 self.copies.insert(device.clone(), copy);
+                self.owned.insert(device.clone(), true);
+                self.touch(device);
                 Ok(self)
             }
         }
     }
 
+    /// Release the copy on `device` back into this `SharedMemory`'s pool, if it has one, or
+    /// drop it outright otherwise.
+    ///
+    /// Errors if `device` is the `latest_location`: that copy is the only one guaranteed to
+    /// hold up-to-date data, so it must not be freed out from under the `SharedMemory` without
+    /// first `sync`ing elsewhere. Also errors if a `sync_async` transfer is still in flight for
+    /// `device`: freeing (or pool-recycling) the buffer it is writing into would let that write
+    /// land in memory no longer owned by this `SharedMemory`, or already handed to another one.
+    pub fn drop_device(&mut self, device: &DeviceType) -> Result<(), Error> {
+        if &self.latest_location == device {
+            return Err(Error::InvalidMemoryAllocation("Cannot drop the device holding the latest copy; sync to another device first."));
+        }
+        if self.pending.contains_key(device) {
+            return Err(Error::TransferInFlight("Cannot drop a device with a transfer in flight; join the SyncHandle first."));
+        }
+        let memory = try!(self.copies.remove(device).ok_or(Error::MissingDestination("SharedMemory does not hold a copy on destination device.")));
+        self.partial.remove(device);
+        self.last_access.borrow_mut().remove(device);
+        self.evicted.remove(device);
+        if self.owned.remove(device).unwrap_or(true) {
+            if let Some(ref pool) = self.pool {
+                pool.borrow_mut().release(device, Self::mem_size(self.cap), memory);
+                return Ok(());
+            }
+        } else {
+            // Imported memory: never run the framework's free routine on a buffer we don't own.
+            mem::forget(memory);
+        }
+        Ok(())
+    }
+
+    /// Set a resident-memory budget, in bytes, for `device`. `evict_stale` frees a tracked,
+    /// non-latest copy whenever its size exceeds the budget configured for its device.
+    pub fn set_budget(&mut self, device: &DeviceType, max_bytes: u64) {
+        self.budgets.insert(device.clone(), max_bytes);
+    }
+
+    /// Free `device`'s tracked, non-latest copy if it exceeds its configured budget, to make
+    /// room for a future allocation on it.
+    ///
+    /// A freed copy is not forgotten: `device` stays tracked, so the next `sync`/`get_synced`
+    /// to it transparently reallocates and re-syncs rather than erroring as untracked. Errors
+    /// only if `device` itself is the `latest_location` and over its own budget, since the
+    /// latest copy can never be evicted without losing the only up-to-date data.
+    pub fn evict_stale(&mut self, device: &DeviceType) -> Result<(), Error> {
+        let over_budget = self.budgets.get(device).map_or(false, |&max| Self::mem_size(self.cap) as u64 > max);
+        if !over_budget {
+            return Ok(());
+        }
+        if device == &self.latest_location {
+            return Err(Error::InvalidMemoryAllocation("Cannot evict the latest copy to satisfy its own budget."));
+        }
+        if self.pending.contains_key(device) {
+            // A `sync_async` transfer is still writing into this copy; freeing or pool-
+            // recycling it now would hand live memory out from under the in-flight DMA/copy
+            // engine. Leave it tracked and try again on the next `evict_stale`.
+            return Ok(());
+        }
+        if let Some(memory) = self.copies.remove(device) {
+            if self.owned.get(device).cloned().unwrap_or(true) {
+                if let Some(ref pool) = self.pool {
+                    pool.borrow_mut().release(device, Self::mem_size(self.cap), memory);
+                }
+            } else {
+                // Imported memory: never run the framework's free routine on it. The next
+                // `reclaim_evicted` allocates a fresh, owned buffer to replace it.
+                mem::forget(memory);
+            }
+            self.partial.remove(device);
+            self.last_access.borrow_mut().remove(device);
+            self.owned.remove(device);
+            self.evicted.insert(device.clone(), ());
+        }
+        Ok(())
+    }
+
+    /// Reallocate and track `device` again if `evict_stale` previously freed its copy. A no-op
+    /// if `device` was never evicted.
+    fn reclaim_evicted(&mut self, device: &DeviceType) -> Result<(), Error> {
+        if self.evicted.remove(device).is_none() {
+            return Ok(());
+        }
+        let bytes = Self::mem_size(self.cap);
+        let claimed = match self.pool {
+            Some(ref pool) => pool.borrow_mut().claim(device, bytes),
+            None => None,
+        };
+        let copy = match claimed {
+            Some(memory) => memory,
+            None => try!(Self::allocate_on(device, bytes)),
+        };
+        self.copies.insert(device.clone(), copy);
+        self.owned.insert(device.clone(), true);
+        self.touch(device);
+        Ok(())
+    }
+
+    /// Record in `last_access` that `device`'s tracked copy was just accessed.
+    fn touch(&self, device: &DeviceType) {
+        if self.copies.contains_key(device) {
+            self.last_access.borrow_mut().insert(device.clone(), Instant::now());
+        }
+    }
+
     /// Returns the device that contains the up-to-date memory copy.
     pub fn latest_device(&self) -> &DeviceType {
         &self.latest_location
@@ -200,6 +777,199 @@ impl<T> SharedMemory<T> {
     }
 }
 
+impl<T> Drop for SharedMemory<T> {
+    fn drop(&mut self) {
+        let bytes = Self::mem_size(self.cap);
+        let pool = self.pool.take();
+        // `ManuallyDrop` suppresses the compiler's own drop glue for `latest_copy`, so taking it
+        // out here is the only place its destructor runs: forgotten if it was imported through
+        // `from_raw` (we never owned it), returned to `pool` if one is set, dropped normally
+        // otherwise.
+        let latest = unsafe { ManuallyDrop::take(&mut self.latest_copy) };
+        if !self.owned.get(&self.latest_location).cloned().unwrap_or(true) {
+            mem::forget(latest);
+        } else if let Some(ref pool) = pool {
+            pool.borrow_mut().release(&self.latest_location, bytes, latest);
+        }
+        // Return every tracked, non-latest copy to the pool (if any) so a future `SharedMemory`
+        // of the same size can claim it instead of allocating.
+        let devices: Vec<DeviceType> = self.copies.keys().cloned().collect();
+        for device in devices {
+            if let Some(memory) = self.copies.remove(&device) {
+                if !self.owned.get(&device).cloned().unwrap_or(true) {
+                    mem::forget(memory);
+                } else if let Some(ref pool) = pool {
+                    pool.borrow_mut().release(&device, bytes, memory);
+                }
+            }
+        }
+    }
+}
+
+/// A raw handle to memory already allocated on a device, for use with
+/// [`SharedMemory::from_raw`][1]/[`export`][2] to interoperate with external GPU libraries or
+/// framework buffers without an extra copy.
+///
+/// [1]: ./struct.SharedMemory.html#method.from_raw
+/// [2]: ./struct.SharedMemory.html#method.export
+#[derive(Debug, Clone, Copy)]
+pub enum ExternalMemory {
+    /// A raw host pointer, for `Native` memory.
+    Native(*mut u8),
+    /// An OpenCL buffer handle (`cl_mem`).
+    OpenCL(u64),
+    /// A raw CUDA device pointer.
+    #[cfg(feature = "cuda")]
+    Cuda(u64),
+}
+
+/// A size-classed cache of freed device buffers, shared between `SharedMemory`s created with
+/// [`SharedMemory::with_pool`][1] so that repeatedly creating and dropping buffers of the same
+/// size doesn't thrash the framework allocator.
+///
+/// Buffers are bucketed by `(DeviceType, size class)`, where the size class is the next
+/// power-of-two byte count at or above the requested size; this lets a buffer freed for one
+/// capacity be reused by a later, slightly smaller request on the same device.
+///
+/// A `MemoryPool` is typically shared through an `Rc<RefCell<MemoryPool>>` so multiple
+/// `SharedMemory`s can claim from and release into it.
+///
+/// [1]: ./struct.SharedMemory.html#method.with_pool
+#[allow(missing_debug_implementations)]
+pub struct MemoryPool {
+    /// Each pooled buffer is kept with the byte size it was released with.
+    free: LinearMap<(DeviceType, usize), Vec<(usize, MemoryType)>>,
+}
+
+impl MemoryPool {
+    /// Create an empty pool.
+    pub fn new() -> MemoryPool {
+        MemoryPool { free: LinearMap::new() }
+    }
+
+    /// Round `bytes` up to the size class it is pooled under.
+    fn size_class(bytes: usize) -> usize {
+        bytes.next_power_of_two()
+    }
+
+    /// Claim a pooled buffer of at least `bytes` on `device`, if one is available.
+    fn claim(&mut self, device: &DeviceType, bytes: usize) -> Option<MemoryType> {
+        match self.free.get_mut(&(device.clone(), Self::size_class(bytes))) {
+            Some(bucket) => match bucket.iter().position(|&(size, _)| size >= bytes) {
+                Some(index) => Some(bucket.swap_remove(index).1),
+                None => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Return a buffer of `bytes` size on `device` to the pool.
+    fn release(&mut self, device: &DeviceType, bytes: usize, memory: MemoryType) {
+        let key = (device.clone(), Self::size_class(bytes));
+        match self.free.get_mut(&key) {
+            Some(bucket) => bucket.push((bytes, memory)),
+            None => { self.free.insert(key, vec![(bytes, memory)]); }
+        }
+    }
+}
+
+/// A host-visible, read-only view over a `SharedMemory<T>` copy, borrowed through
+/// [`SharedMemory::map_read`][1].
+///
+/// [1]: ./struct.SharedMemory.html#method.map_read
+pub struct MappedSlice<'a, T: 'a> {
+    slice: &'a [T],
+}
+
+impl<'a, T> Deref for MappedSlice<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+
+/// A host-visible, mutable view over a `SharedMemory<T>` copy, borrowed through
+/// [`SharedMemory::map_write`][1].
+///
+/// On drop, the written-to device is synced back to and becomes the new `latest_location`, so
+/// other devices are transparently re-synced the next time they are accessed.
+///
+/// [1]: ./struct.SharedMemory.html#method.map_write
+pub struct MappedSliceMut<'a, T: 'a> {
+    shared: &'a mut SharedMemory<T>,
+    /// The `Native` device the slice is actually borrowed from.
+    host: DeviceType,
+    /// The device that should become `latest_location` once writes are visible there.
+    writeback: DeviceType,
+}
+
+impl<'a, T> Deref for MappedSliceMut<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.shared.peek(&self.host).and_then(|mem| mem.as_native()).map(|flat| flat.as_slice())
+            .expect("mapped device copy missing")
+    }
+}
+
+impl<'a, T> DerefMut for MappedSliceMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        let host = self.host.clone();
+        self.shared.get_mut(&host).and_then(|mem| mem.as_mut_native()).map(|flat| flat.as_mut_slice())
+            .expect("mapped device copy missing")
+    }
+}
+
+impl<'a, T> Drop for MappedSliceMut<'a, T> {
+    fn drop(&mut self) {
+        // Best-effort: a `Drop` impl cannot propagate the error, and a failure here only means
+        // the writeback device keeps serving its previous, pre-map contents until the next
+        // explicit `sync`.
+        let _ = self.shared.sync(&self.writeback);
+    }
+}
+
+/// A handle to an asynchronous, stream-enqueued memory transfer started by
+/// [`SharedMemory::sync_async`][1].
+///
+/// The transfer is not guaranteed to be visible on the destination device until this handle
+/// has been joined. Dropping a `SyncHandle` without joining it leaves the transfer marked as
+/// pending on the `SharedMemory` it came from, so a later `get`, `get_mut` or `sync` on that
+/// device blocks to join it and catch up instead of racing the copy, while a later
+/// `sync_async` on it returns `Error::TransferInFlight`.
+///
+/// [1]: ./struct.SharedMemory.html#method.sync_async
+pub struct SyncHandle {
+    source: DeviceType,
+    destination: DeviceType,
+    stream: Stream,
+}
+
+impl SyncHandle {
+    /// Blocks the calling thread until the enqueued transfer has completed, then makes
+    /// `destination` the new latest location on `shared`.
+    pub fn join<T>(self, shared: &mut SharedMemory<T>) -> Result<(), Error> {
+        try!(self.stream.sync());
+        shared.finish_async(&self.destination)
+    }
+
+    /// Returns `true` if the transfer behind this handle has already completed.
+    pub fn is_ready(&self) -> bool {
+        self.stream.is_done()
+    }
+
+    /// Returns the device the transfer reads from.
+    pub fn source(&self) -> &DeviceType {
+        &self.source
+    }
+
+    /// Returns the device the transfer writes to.
+    pub fn destination(&self) -> &DeviceType {
+        &self.destination
+    }
+}
+
 /// Errors than can occur when synchronizing memory.
 #[derive(Debug, Copy, Clone)]
 pub enum Error {
@@ -211,6 +981,8 @@ pub enum Error {
     InvalidMemory(&'static str),
     /// No memory allocation on specified device happened.
     InvalidMemoryAllocation(&'static str),
+    /// An asynchronous transfer is already in flight for the requested device.
+    TransferInFlight(&'static str),
     /// Framework error at memory allocation.
     MemoryAllocationError(::device::Error),
     /// Framework error at memory synchronization.
@@ -224,6 +996,7 @@ impl fmt::Display for Error {
             Error::MissingDestination(ref err) => write!(f, "{:?}", err),
             Error::InvalidMemory(ref err) => write!(f, "{:?}", err),
             Error::InvalidMemoryAllocation(ref err) => write!(f, "{:?}", err),
+            Error::TransferInFlight(ref err) => write!(f, "{:?}", err),
             Error::MemoryAllocationError(ref err) => write!(f, "{}", err),
             Error::MemorySynchronizationError(ref err) => write!(f, "{}", err),
         }
@@ -237,6 +1010,7 @@ impl error::Error for Error {
             Error::MissingDestination(ref err) => err,
             Error::InvalidMemory(ref err) => err,
             Error::InvalidMemoryAllocation(ref err) => err,
+            Error::TransferInFlight(ref err) => err,
             Error::MemoryAllocationError(ref err) => err.description(),
             Error::MemorySynchronizationError(ref err) => err.description(),
         }
@@ -248,6 +1022,7 @@ impl error::Error for Error {
             Error::MissingDestination(_) => None,
             Error::InvalidMemory(_) => None,
             Error::InvalidMemoryAllocation(_) => None,
+            Error::TransferInFlight(_) => None,
             Error::MemoryAllocationError(ref err) => Some(err),
             Error::MemorySynchronizationError(ref err) => Some(err),
         }
@@ -259,3 +1034,57 @@ impl From<Error> for ::error::Error {
         ::error::Error::SharedMemory(err)
     }
 }
+
+// These exercise the single-device paths directly. Covering the multi-device sync/eviction
+// paths and `sync_async` would need a second tracked peer device or a framework `Stream`,
+// neither of which this crate exposes a way to construct outside a concrete GPU framework.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use framework::IFramework;
+    use frameworks::Native;
+
+    fn native_device() -> DeviceType {
+        let framework = Native::new();
+        framework.new_device(framework.hardwares()).unwrap()
+    }
+
+    #[test]
+    fn get_mut_then_get_round_trips_through_latest_copy() {
+        let device = native_device();
+        let mut shared = SharedMemory::<i32>::new(&device, 4).unwrap();
+        {
+            let mem = shared.get_mut(&device).unwrap().as_mut_native().unwrap();
+            mem.as_mut_slice().clone_from_slice(&[1, 2, 3, 4]);
+        }
+        assert_eq!(shared.get(&device).unwrap().as_native().unwrap().as_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn evict_stale_is_a_noop_below_budget() {
+        let device = native_device();
+        let mut shared = SharedMemory::<i32>::new(&device, 4).unwrap();
+        shared.set_budget(&device, u64::max_value());
+        assert!(shared.evict_stale(&device).is_ok());
+        assert!(shared.get(&device).is_some());
+    }
+
+    #[test]
+    fn evict_stale_errors_on_the_latest_copy_over_budget() {
+        let device = native_device();
+        let mut shared = SharedMemory::<i32>::new(&device, 4).unwrap();
+        shared.set_budget(&device, 0);
+        assert!(shared.evict_stale(&device).is_err());
+    }
+
+    #[test]
+    fn from_raw_copy_survives_drop_of_the_importing_handle() {
+        let device = native_device();
+        let mut owner = SharedMemory::<i32>::new(&device, 4).unwrap();
+        owner.get_mut(&device).unwrap().as_mut_native().unwrap().as_mut_slice().clone_from_slice(&[9, 9, 9, 9]);
+        let handle = owner.export(&device).unwrap();
+        let imported = SharedMemory::<i32>::from_raw(&device, handle, 4).unwrap();
+        drop(imported);
+        assert_eq!(owner.get(&device).unwrap().as_native().unwrap().as_slice(), &[9, 9, 9, 9]);
+    }
+}